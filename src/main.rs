@@ -1,16 +1,31 @@
 use std::{
-    env, fs,
-    io::{BufRead, Write},
+    env,
+    fs::{self, File},
+    io::{BufRead, BufReader, Write},
     path::{Path, PathBuf},
     str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
 };
 
+use anyhow::Context;
+use log::info;
+
+const PROGRESS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
     let mut args = env::args();
-    if args.len() != 4 {
+    if args.len() != 4 && args.len() != 5 {
         panic!(
             "Incorrect argument set
-    please, pass <PATH_TO_INPUT_FILE> <ID_OF_USER_TO_COMPARE> <PATH_TO_OUTPUT_FILE>"
+    please, pass <PATH_TO_INPUT_FILE> <ID_OF_USER_TO_COMPARE> <PATH_TO_OUTPUT_FILE> [METRIC]
+    METRIC is one of: inversions (default), kendall-tau, footrule"
         );
     }
 
@@ -21,33 +36,68 @@ fn main() -> anyhow::Result<()> {
     }
     let target_user_id = args.next().unwrap().parse::<u32>()?;
     let output_file = PathBuf::from_str(&args.next().unwrap())?;
+    let metric = args
+        .next()
+        .map(|value| value.parse::<SimilarityMetric>())
+        .transpose()?
+        .unwrap_or(SimilarityMetric::Inversions);
 
-    make_recommendation_rating(&input_file, target_user_id, &output_file)?;
+    make_recommendation_rating(&input_file, target_user_id, &output_file, metric)?;
 
     Ok(())
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SimilarityMetric {
+    Inversions,
+    NormalizedKendallTau,
+    SpearmanFootrule,
+}
+
+impl SimilarityMetric {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Inversions => "inversions",
+            Self::NormalizedKendallTau => "kendall-tau",
+            Self::SpearmanFootrule => "footrule",
+        }
+    }
+}
+
+impl FromStr for SimilarityMetric {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "inversions" => Ok(Self::Inversions),
+            "kendall-tau" => Ok(Self::NormalizedKendallTau),
+            "footrule" => Ok(Self::SpearmanFootrule),
+            other => anyhow::bail!(
+                "unknown metric '{other}', expected one of: inversions, kendall-tau, footrule"
+            ),
+        }
+    }
+}
+
 fn make_recommendation_rating(
     input_file: &Path,
     target_user_id: u32,
     output_file: &Path,
+    metric: SimilarityMetric,
 ) -> anyhow::Result<()> {
     let ratings = parse_input_file(input_file)?;
 
-    let mut collisions = get_rating_collisions(&ratings, target_user_id);
+    let collisions = get_rating_collisions(&ratings, target_user_id)?;
 
-    let mut inversions = collisions
-        .iter_mut()
-        .map(|(id, collision)| (*id, sort_and_count_inversions(collision)))
-        .collect::<Vec<_>>();
+    let mut scores = compute_similarity_parallel(collisions, metric);
 
-    inversions.sort_by(|a, b| a.1.cmp(&b.1));
+    scores.sort_by(|a, b| a.1.total_cmp(&b.1));
 
     let mut content_to_write = Vec::new();
 
     writeln!(content_to_write, "{target_user_id}")?;
-    for (id, inversion) in &inversions {
-        writeln!(content_to_write, "{id} {inversion}")?;
+    for (id, score) in &scores {
+        writeln!(content_to_write, "{id} {} {score}", metric.name())?;
     }
 
     if let Some(parent) = output_file.parent() {
@@ -60,10 +110,11 @@ fn make_recommendation_rating(
 }
 
 fn parse_input_file(input_file: &Path) -> anyhow::Result<Vec<(u32, Vec<u32>)>> {
-    let input = fs::read(input_file)?;
-    let mut input_lines = input.lines();
+    let mut lines = IncludeLineReader::new(input_file)?;
 
-    let info_line = input_lines.next().unwrap()?;
+    let info_line = lines
+        .next()
+        .context("input file has no header line after resolving includes")??;
     let mut info_line_split = info_line.split(' ');
 
     let _users = info_line_split.next().unwrap().parse::<u32>()?;
@@ -71,7 +122,7 @@ fn parse_input_file(input_file: &Path) -> anyhow::Result<Vec<(u32, Vec<u32>)>> {
 
     let mut ratings = Vec::new();
 
-    for user_line in input_lines {
+    for user_line in lines {
         let user_line = user_line?;
         let mut user_line_split = user_line.split(' ');
         let user_id = user_line_split.next().unwrap().parse::<u32>()?;
@@ -84,7 +135,85 @@ fn parse_input_file(input_file: &Path) -> anyhow::Result<Vec<(u32, Vec<u32>)>> {
     Ok(ratings)
 }
 
-fn get_rating_collisions(ratings: &[(u32, Vec<u32>)], target_user_id: u32) -> Vec<(u32, Vec<u32>)> {
+// Splices `%include <path>` directives in depth-first, tracking open files
+// to reject cycles instead of recursing forever.
+struct IncludeLineReader {
+    stack: Vec<(BufReader<File>, PathBuf)>,
+    open_paths: Vec<PathBuf>,
+}
+
+impl IncludeLineReader {
+    fn new(path: &Path) -> anyhow::Result<Self> {
+        let mut reader = Self {
+            stack: Vec::new(),
+            open_paths: Vec::new(),
+        };
+        reader.push(path)?;
+        Ok(reader)
+    }
+
+    fn push(&mut self, path: &Path) -> anyhow::Result<()> {
+        let canonical_path = path
+            .canonicalize()
+            .with_context(|| format!("failed to resolve input file {}", path.display()))?;
+        if self.open_paths.contains(&canonical_path) {
+            anyhow::bail!("include cycle detected at {}", path.display());
+        }
+
+        let file = File::open(path)
+            .with_context(|| format!("failed to open input file {}", path.display()))?;
+
+        self.open_paths.push(canonical_path);
+        self.stack.push((BufReader::new(file), path.to_path_buf()));
+
+        Ok(())
+    }
+}
+
+impl Iterator for IncludeLineReader {
+    type Item = anyhow::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let current_path = self.stack.last()?.1.clone();
+
+            let mut line = String::new();
+            let bytes_read = match self.stack.last_mut().unwrap().0.read_line(&mut line) {
+                Ok(bytes_read) => bytes_read,
+                Err(error) => return Some(Err(error.into())),
+            };
+
+            if bytes_read == 0 {
+                self.stack.pop();
+                self.open_paths.pop();
+                continue;
+            }
+
+            let trimmed = line.trim_end_matches(['\n', '\r']);
+            let content = trimmed.trim();
+
+            if content.is_empty() || content.starts_with('#') || content.starts_with(';') {
+                continue;
+            }
+
+            if let Some(included_path) = content.strip_prefix("%include ") {
+                let parent_dir = current_path.parent().unwrap_or_else(|| Path::new("."));
+                let included_path = parent_dir.join(included_path.trim());
+                if let Err(error) = self.push(&included_path) {
+                    return Some(Err(error));
+                }
+                continue;
+            }
+
+            return Some(Ok(trimmed.to_string()));
+        }
+    }
+}
+
+fn get_rating_collisions(
+    ratings: &[(u32, Vec<u32>)],
+    target_user_id: u32,
+) -> anyhow::Result<Vec<(u32, Vec<u32>)>> {
     let users = ratings.len();
     let films = ratings.first().unwrap().1.len();
 
@@ -96,21 +225,153 @@ fn get_rating_collisions(ratings: &[(u32, Vec<u32>)], target_user_id: u32) -> Ve
         .unwrap()
         .1;
 
+    // Inverse permutation: rank_to_index[rank - 1] is the film column ranked
+    // `rank` by the target user, computed once so building each collision is
+    // a single O(films) pass instead of an O(films) `position` scan per film.
+    // `written` guards against a row that isn't a clean permutation of
+    // `1..=films` (duplicate, missing, or out-of-range rank).
+    let mut rank_to_index = vec![0usize; films];
+    let mut written = vec![false; films];
+    for (index, &rank) in target_user_rating.iter().enumerate() {
+        let slot = rank
+            .checked_sub(1)
+            .map(|slot| slot as usize)
+            .filter(|&slot| slot < films)
+            .with_context(|| {
+                format!(
+                    "user {target_user_id} has an out-of-range rank {rank} (expected 1..={films})"
+                )
+            })?;
+        if written[slot] {
+            anyhow::bail!("user {target_user_id} ranks two films as {rank}");
+        }
+        rank_to_index[slot] = index;
+        written[slot] = true;
+    }
+    if written.iter().any(|&slot_written| !slot_written) {
+        anyhow::bail!("user {target_user_id} is missing a rank in 1..={films}");
+    }
+
     let mut collisions = Vec::with_capacity(users - 1);
 
     for (id, rating) in ratings.iter().filter(|(id, _)| *id != target_user_id) {
-        let mut collision = Vec::with_capacity(films);
-        for i in 1..=films {
-            let index = target_user_rating
-                .iter()
-                .position(|mark| *mark == i as u32)
-                .unwrap();
-            collision.push(rating[index]);
+        if rating.len() != films {
+            anyhow::bail!("user {id} has {} ratings, expected {films}", rating.len());
         }
+        let collision = rank_to_index
+            .iter()
+            .map(|&index| rating[index])
+            .collect::<Vec<_>>();
         collisions.push((*id, collision));
     }
 
-    collisions
+    Ok(collisions)
+}
+
+fn worker_thread_count() -> usize {
+    env::var("RECOMMEND_THREADS")
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .filter(|count| *count > 0)
+        .unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        })
+}
+
+// Result is unordered; callers sort it afterwards.
+fn compute_similarity_parallel(
+    collisions: Vec<(u32, Vec<u32>)>,
+    metric: SimilarityMetric,
+) -> Vec<(u32, f64)> {
+    let total = collisions.len();
+    let thread_count = worker_thread_count().min(total.max(1));
+
+    let progress = Arc::new(AtomicUsize::new(0));
+
+    let chunk_size = total.div_ceil(thread_count.max(1)).max(1);
+    let mut chunks = Vec::new();
+    let mut remaining = collisions;
+    while !remaining.is_empty() {
+        let split_at = chunk_size.min(remaining.len());
+        let tail = remaining.split_off(split_at);
+        chunks.push(remaining);
+        remaining = tail;
+    }
+
+    thread::scope(|scope| {
+        // Signals the reporter to stop even if a worker below panics and
+        // `progress` never reaches `total`, so a panic can't hang the scope.
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+
+        let progress_reporter = scope.spawn({
+            let progress = Arc::clone(&progress);
+            move || loop {
+                let processed = progress.load(Ordering::Relaxed);
+                info!("processed {processed}/{total} users");
+                if processed >= total || done_rx.recv_timeout(PROGRESS_POLL_INTERVAL).is_ok() {
+                    break;
+                }
+            }
+        });
+
+        let handles = chunks
+            .into_iter()
+            .map(|chunk| {
+                let progress = Arc::clone(&progress);
+                scope.spawn(move || {
+                    let mut result = Vec::with_capacity(chunk.len());
+                    for (id, mut collision) in chunk {
+                        let score = score_collision(&mut collision, metric);
+                        result.push((id, score));
+                        progress.fetch_add(1, Ordering::Relaxed);
+                    }
+                    result
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let worker_results = handles
+            .into_iter()
+            .map(|handle| handle.join())
+            .collect::<Vec<_>>();
+
+        let _ = done_tx.send(());
+        progress_reporter
+            .join()
+            .expect("progress reporter thread panicked");
+
+        worker_results
+            .into_iter()
+            .flat_map(|worker_result| worker_result.expect("worker thread panicked"))
+            .collect::<Vec<_>>()
+    })
+}
+
+fn score_collision(collision: &mut [u32], metric: SimilarityMetric) -> f64 {
+    match metric {
+        SimilarityMetric::Inversions => sort_and_count_inversions(collision) as f64,
+        SimilarityMetric::NormalizedKendallTau => {
+            let films = collision.len() as u64;
+            let max_inversions = films * films.saturating_sub(1) / 2;
+            let inversions = sort_and_count_inversions(collision);
+            if max_inversions == 0 {
+                0.0
+            } else {
+                inversions as f64 / max_inversions as f64
+            }
+        }
+        SimilarityMetric::SpearmanFootrule => spearman_footrule(collision),
+    }
+}
+
+fn spearman_footrule(collision: &[u32]) -> f64 {
+    collision
+        .iter()
+        .enumerate()
+        .map(|(position, &other_rank)| (other_rank as i64 - (position as i64 + 1)).unsigned_abs())
+        .sum::<u64>() as f64
 }
 
 fn sort_and_count_inversions(array: &mut [u32]) -> u32 {
@@ -169,18 +430,194 @@ fn merge_and_count_split_inversions(array: &mut [u32], mid: usize) -> u32 {
 #[cfg(test)]
 mod tests {
     use std::{
-        fs,
+        env, fs,
         path::{Path, PathBuf},
         str::FromStr,
     };
 
-    use crate::make_recommendation_rating;
+    use crate::{
+        make_recommendation_rating, score_collision, spearman_footrule, IncludeLineReader,
+        SimilarityMetric,
+    };
+
+    const DIFF_CONTEXT_SIZE: usize = 3;
+
+    fn include_test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "fice_dsa2_lab2_include_tests_{name}_{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn include_line_reader_skips_comments_and_blanks() -> anyhow::Result<()> {
+        let dir = include_test_dir("comments");
+        let file = dir.join("input.txt");
+        fs::write(&file, "# comment\n; also a comment\n\n2 2\n1 1 2\n")?;
+
+        let lines = IncludeLineReader::new(&file)?.collect::<anyhow::Result<Vec<_>>>()?;
+
+        assert_eq!(lines, vec!["2 2".to_string(), "1 1 2".to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn include_line_reader_splices_nested_include_depth_first() -> anyhow::Result<()> {
+        let dir = include_test_dir("nested");
+        fs::write(dir.join("counts.txt"), "2 2\n")?;
+        fs::write(dir.join("header.txt"), "%include counts.txt\n1 1 2\n")?;
+        fs::write(dir.join("main.txt"), "%include header.txt\n2 2 1\n")?;
+
+        let lines =
+            IncludeLineReader::new(&dir.join("main.txt"))?.collect::<anyhow::Result<Vec<_>>>()?;
+
+        assert_eq!(
+            lines,
+            vec!["2 2".to_string(), "1 1 2".to_string(), "2 2 1".to_string()]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn include_line_reader_detects_cycle() -> anyhow::Result<()> {
+        let dir = include_test_dir("cycle");
+        fs::write(dir.join("a.txt"), "%include b.txt\n")?;
+        fs::write(dir.join("b.txt"), "%include a.txt\n")?;
+
+        let result =
+            IncludeLineReader::new(&dir.join("a.txt"))?.collect::<anyhow::Result<Vec<_>>>();
+
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn spearman_footrule_sums_rank_displacements() {
+        // target ranked films [1, 2, 3, 4]; other user's ranks at those
+        // positions are [2, 1, 4, 3], each off by exactly one.
+        assert_eq!(spearman_footrule(&[2, 1, 4, 3]), 4.0);
+        assert_eq!(spearman_footrule(&[1, 2, 3, 4]), 0.0);
+    }
+
+    #[test]
+    fn score_collision_inversions_counts_discordant_pairs() {
+        let mut collision = [2, 1, 4, 3];
+        assert_eq!(
+            score_collision(&mut collision, SimilarityMetric::Inversions),
+            2.0
+        );
+    }
+
+    #[test]
+    fn score_collision_normalized_kendall_tau_divides_by_max_pairs() {
+        let mut collision = [4, 3, 2, 1];
+        assert_eq!(
+            score_collision(&mut collision, SimilarityMetric::NormalizedKendallTau),
+            1.0
+        );
+
+        let mut collision = [1, 2, 3, 4];
+        assert_eq!(
+            score_collision(&mut collision, SimilarityMetric::NormalizedKendallTau),
+            0.0
+        );
+    }
 
+    #[test]
+    fn score_collision_footrule_matches_direct_sum() {
+        let mut collision = [2, 1, 4, 3];
+        assert_eq!(
+            score_collision(&mut collision, SimilarityMetric::SpearmanFootrule),
+            4.0
+        );
+    }
+
+    // Set UPDATE_EXPECT=1 to regenerate golden files instead of comparing.
     fn compare_output(expected: &Path, actual: &Path) -> anyhow::Result<bool> {
-        let expected = fs::read(expected)?;
-        let actual = fs::read(actual)?;
+        if env::var("UPDATE_EXPECT").as_deref() == Ok("1") {
+            fs::copy(actual, expected)?;
+            return Ok(true);
+        }
+
+        let expected_content = fs::read_to_string(expected)?;
+        let actual_content = fs::read_to_string(actual)?;
+
+        if expected_content == actual_content {
+            return Ok(true);
+        }
+
+        eprint!("{}", print_diff(&expected_content, &actual_content));
+
+        Ok(false)
+    }
+
+    fn print_diff(expected: &str, actual: &str) -> String {
+        let expected_lines = expected.lines().collect::<Vec<_>>();
+        let actual_lines = actual.lines().collect::<Vec<_>>();
+
+        let total_lines = expected_lines.len().max(actual_lines.len());
 
-        Ok(expected == actual)
+        let mut diff = String::new();
+        for line_number in 0..total_lines {
+            let expected_line = expected_lines.get(line_number);
+            let actual_line = actual_lines.get(line_number);
+
+            if expected_line == actual_line {
+                continue;
+            }
+
+            let context_start = line_number.saturating_sub(DIFF_CONTEXT_SIZE);
+            let context_end = (line_number + DIFF_CONTEXT_SIZE + 1).min(total_lines);
+
+            diff.push_str(&format!("--- mismatch at line {} ---\n", line_number + 1));
+            for context_line in context_start..context_end {
+                if context_line == line_number {
+                    if let Some(expected_line) = expected_line {
+                        diff.push_str(&format!("-{expected_line}\n"));
+                    }
+                    if let Some(actual_line) = actual_line {
+                        diff.push_str(&format!("+{actual_line}\n"));
+                    }
+                } else if let Some(line) = expected_lines.get(context_line) {
+                    diff.push_str(&format!(" {line}\n"));
+                }
+            }
+        }
+        diff
+    }
+
+    #[test]
+    fn print_diff_marks_mismatched_line_with_context() {
+        let expected = "a\nb\nc\nd\ne\n";
+        let actual = "a\nb\nX\nd\ne\n";
+
+        let diff = print_diff(expected, actual);
+
+        assert!(diff.contains("mismatch at line 3"));
+        assert!(diff.contains("-c"));
+        assert!(diff.contains("+X"));
+        assert!(diff.contains(" b"));
+        assert!(diff.contains(" d"));
+    }
+
+    #[test]
+    fn compare_output_bless_overwrites_expected_file() -> anyhow::Result<()> {
+        let dir = include_test_dir("bless");
+        let expected = dir.join("expected.txt");
+        let actual = dir.join("actual.txt");
+        fs::write(&expected, "old content\n")?;
+        fs::write(&actual, "new content\n")?;
+
+        env::set_var("UPDATE_EXPECT", "1");
+        let result = compare_output(&expected, &actual);
+        env::remove_var("UPDATE_EXPECT");
+
+        assert!(result?);
+        assert_eq!(fs::read_to_string(&expected)?, "new content\n");
+        Ok(())
     }
 
     #[test]
@@ -239,7 +676,12 @@ mod tests {
                     fs::create_dir_all(parent)?;
                 }
 
-                make_recommendation_rating(&input_file.path(), target_user_id, &output_file_path)?;
+                make_recommendation_rating(
+                    &input_file.path(),
+                    target_user_id,
+                    &output_file_path,
+                    SimilarityMetric::Inversions,
+                )?;
 
                 assert!(
                     compare_output(&test_case_file.path(), &output_file_path).unwrap(),